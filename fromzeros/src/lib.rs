@@ -9,6 +9,19 @@ pub unsafe trait FromZeros {
   {
     unsafe { std::mem::zeroed() }
   }
+
+  // Fallibly construct the all-zero value of this type.
+  //
+  // By default the all-zero bit pattern is always a valid value, so this
+  // simply yields `Some(Self::zeroed())`. The `#[derive(FromZeros)]` macro
+  // overrides this for types carrying a `#[fromzeros(validator = ...)]`
+  // attribute, returning `None` when the validator rejects the zeroed value.
+  #[inline(always)]
+  fn try_zeroed() -> Option<Self>
+  where Self: Sized
+  {
+    Some(Self::zeroed())
+  }
 }
 
 pub fn zeroed<T>() -> T
@@ -18,6 +31,40 @@ where
     unsafe { std::mem::zeroed() }
 }
 
+// A type is `FromBytes` if every possible arrangement of bytes is a valid
+// value. Anything inhabited by all byte patterns is trivially inhabited by the
+// all-zero pattern, so every `FromBytes` type is also `FromZeros`.
+pub unsafe trait FromBytes: FromZeros {}
+
+macro_rules! impl_frombytes{
+  ($($ty : ty)*) => {$(unsafe impl FromBytes for $ty {})*}
+}
+
+// Note that `bool` and `char` are deliberately absent: they are `FromZeros`,
+// but not every byte pattern is a valid `bool` or `char`.
+impl_frombytes!{
+  i8
+  i16
+  i32
+  i64
+  i128
+  isize
+  f32
+  f64
+  u8
+  u16
+  u32
+  u64
+  u128
+  usize
+}
+
+unsafe impl<T: FromBytes> FromBytes for *const T {}
+unsafe impl<T: FromBytes> FromBytes for *mut T {}
+
+unsafe impl<T: FromBytes> FromBytes for [T] {}
+unsafe impl<T: FromBytes, const N: usize> FromBytes for [T; {N}] {}
+
 macro_rules! impl_fromzeros{
   ($($ty : ty)*) => {$(unsafe impl FromZeros for $ty {})*}
 }
@@ -47,3 +94,7 @@ unsafe impl<T: FromZeros> FromZeros for *mut T {}
 
 unsafe impl<T> FromZeros for [T] {}
 unsafe impl<T: FromZeros, const N: usize> FromZeros for [T; {N}] {}
+
+// `PhantomData` occupies no storage, so its zero value is valid regardless of
+// `T`. Bounding on `T` here would defeat the point of a phantom field.
+unsafe impl<T: ?Sized> FromZeros for std::marker::PhantomData<T> {}
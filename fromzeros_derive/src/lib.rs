@@ -1,72 +1,303 @@
-#![feature(option_result_contains)]
-
 extern crate proc_macro;
 
-use if_chain::if_chain;
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
 use quote::{quote, quote_spanned};
 use syn;
 
-#[proc_macro_derive(FromZeros)]
+mod repr;
+
+#[proc_macro_derive(FromZeros, attributes(fromzeros))]
 pub fn from_zeros_derive(input: TokenStream) -> TokenStream {
-  let ast : syn::DeriveInput = syn::parse(input).unwrap();
+  let tokens = syn::parse(input)
+    .map_err(|err| vec![err])
+    .and_then(derive_fromzeros)
+    .unwrap_or_else(print_all_errors);
+  tokens.into()
+}
+
+// collapse a list of accumulated errors into a stream of `compile_error!`
+// invocations, so every problem is reported with its own span rather than an
+// opaque proc-macro panic
+fn print_all_errors(errors: Vec<syn::Error>) -> TokenStream2 {
+  let errors = errors.iter().map(syn::Error::to_compile_error);
+  quote! { #(#errors)* }
+}
+
+// the parsed contents of a `#[fromzeros(validator = ...)]` attribute
+struct ValidatorAttr {
+  expr: syn::Expr,
+}
+
+impl syn::parse::Parse for ValidatorAttr {
+  fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+    let content;
+    syn::parenthesized!(content in input);
+    let key: syn::Ident = content.parse()?;
+    if key != "validator" {
+      return Err(syn::Error::new(key.span(), "expected `validator`"));
+    }
+    content.parse::<syn::Token![=]>()?;
+    let expr: syn::Expr = content.parse()?;
+    Ok(ValidatorAttr { expr })
+  }
+}
+
+// extract the validator expression from a `#[fromzeros(validator = ...)]`
+// attribute, if one is present
+fn validator_attr(attrs: &[syn::Attribute])
+  -> Result<Option<syn::Expr>, Vec<syn::Error>>
+{
+  let attr = attrs.iter()
+    .find(|attr| attr.path.segments.len() == 1
+      && attr.path.segments[0].ident == "fromzeros");
+
+  match attr {
+    None => Ok(None),
+    Some(attr) => syn::parse2::<ValidatorAttr>(attr.tts.clone())
+      .map(|parsed| Some(parsed.expr))
+      .map_err(|err| vec![err]),
+  }
+}
 
+fn derive_fromzeros(ast: syn::DeriveInput)
+  -> Result<TokenStream2, Vec<syn::Error>>
+{
   let name = &ast.ident;
   let attrs = &ast.attrs;
-  let generics = ast.generics;
+  let generics = ast.generics.clone();
+
+  let validator = validator_attr(attrs)?;
+  let validator = validator.as_ref();
+
+  let repr = repr::Repr::from_attrs(attrs);
+
+  // contradictory repr hints (e.g. `repr(packed)` together with
+  // `repr(align(N))`) are rejected up-front, whatever the data shape
+  if let Some(mismatch) = repr.mismatch() {
+    return Err(vec![syn::Error::new_spanned(name,
+      format!("{}: {}", name, mismatch))]);
+  }
 
   match ast.data {
     // `FromZeros` may be implemented for any struct whose fields all implement
     // `FromZeros`.
-    syn::Data::Struct(data)
-      => impl_fromzeros(name, generics, None, &data.fields),
+    //
+    // A `#[repr(transparent)]` struct takes the same per-field path; the
+    // transparent arm only adds a check that the struct has at least one
+    // field. It does not single out the non-zero-sized field.
+    syn::Data::Struct(ref data) => {
+      if repr.is_transparent() {
+        impl_fromzeros_transparent(name, generics, &data.fields, validator)
+      } else {
+        Ok(impl_fromzeros_with(name, generics, None, &data.fields, validator))
+      }
+    },
 
     // `FromZeros` may be implemented for any union in which there exists any
     // variant that implements `FromZeros`. Unfortunately, this 'any'
     // requirement is not expressible by a macro. We therefore require that all
     // variants implement `FromZeros`.
-    syn::Data::Union(data)
-      => impl_fromzeros(name, generics, None, &data.fields.into()),
+    syn::Data::Union(ref data)
+      => Ok(impl_fromzeros_with(name, generics, None,
+            &syn::Fields::Named(data.fields.clone()), validator)),
 
     // `FromZeros` may be implemented for any enum whose memory layout is
     // well-defined and possesses a zero-discriminant variant in which all
     // fields implement `FromZeros`.
     //
     // An enum's layout is well-defined if either:
-    //  * it is a C-like enum
-    //  * it uses a primitive repr
+    //  * it is a fieldless (C-like) enum
+    //  * it uses the `C` repr
+    //  * it uses a primitive-integer repr
     //
-    // Such an enum will have a zero discriminant if:
-    //  * there exists a variant with the explicit discriminant '0'
-    //  * the first variant does not have an explicit discriminant
-    syn::Data::Enum(ref data)
-      => {
-      if !(is_clike(data) || has_primitive_repr(attrs)) {
-        panic!("{} must be either C-like, or use a primitive repr.", name);
+    // Such an enum will have a zero discriminant if a variant resolves to the
+    // discriminant '0' (see `zero_variant`).
+    syn::Data::Enum(ref data) => {
+      let mut errors = Vec::new();
+
+      if !(is_clike(data) || repr.is_c() || repr.is_primitive()) {
+        errors.push(syn::Error::new_spanned(name,
+          format!("{} must be either C-like, or use a primitive repr.", name)));
+      }
+
+      match zero_variant(data) {
+        Some(variant) if errors.is_empty()
+          => Ok(impl_fromzeros_with(name, generics, Some(&variant.ident),
+                &variant.fields, validator)),
+        Some(_)
+          => Err(errors),
+        None => {
+          errors.push(syn::Error::new_spanned(name, format!(
+            "{} does not have a variant with a provably-zero discriminant.",
+            name)));
+          Err(errors)
+        },
+      }
+    },
+  }
+}
+
+#[proc_macro_derive(FromBytes)]
+pub fn from_bytes_derive(input: TokenStream) -> TokenStream {
+  let tokens = syn::parse(input)
+    .map_err(|err| vec![err])
+    .and_then(derive_frombytes)
+    .unwrap_or_else(print_all_errors);
+  tokens.into()
+}
+
+fn derive_frombytes(ast: syn::DeriveInput)
+  -> Result<TokenStream2, Vec<syn::Error>>
+{
+  let name = &ast.ident;
+  let attrs = &ast.attrs;
+  let generics = ast.generics.clone();
+
+  match ast.data {
+    // A struct is `FromBytes` when all of its fields are `FromBytes`.
+    syn::Data::Struct(ref data)
+      => Ok(impl_frombytes(name, generics, &data.fields)),
+
+    // A union is `FromBytes` when all of its fields are `FromBytes`.
+    syn::Data::Union(ref data)
+      => Ok(impl_frombytes(name, generics,
+            &syn::Fields::Named(data.fields.clone()))),
+
+    // An enum is `FromBytes` only if it has a well-defined backing integer and
+    // its variants enumerate every value of that integer; otherwise some bit
+    // patterns correspond to no variant.
+    syn::Data::Enum(ref data) => {
+      let mut errors = Vec::new();
+
+      match backing_repr_bits(attrs) {
+        None => errors.push(syn::Error::new_spanned(name, format!(
+          "{} must use a primitive repr to derive FromBytes.", name))),
+        Some(bits) => {
+          if !enum_covers_repr(data.variants.len(), bits) {
+            errors.push(syn::Error::new_spanned(name, format!(
+              "{} does not enumerate every value of its repr integer, so not \
+               every byte pattern is a valid variant.", name)));
+          }
+        },
       }
 
-      if let Some(variant) = zero_variant(data) {
-        impl_fromzeros(name, generics, Some(&variant.ident), &variant.fields)
+      // every variant's fields must themselves be `FromBytes`
+      let fields: Vec<&syn::Field> = data.variants.iter()
+        .flat_map(|variant| variant.fields.iter())
+        .collect();
+
+      if errors.is_empty() {
+        Ok(impl_frombytes_bounds(name, generics, fields.into_iter()))
       } else {
-        panic!("{} does not have a variant with a provably-zero discriminant.");
+        Err(errors)
       }
+    },
+  }
+}
+
+// implement `FromBytes` for a given struct or union
+fn impl_frombytes(
+  name     : &syn::Ident,
+  generics : syn::Generics,
+  fields   : &syn::Fields,
+) -> TokenStream2
+{
+  impl_frombytes_bounds(name, generics, fields.iter())
+}
+
+// emit the marker `FromBytes` impl, bounding each field type on `FromBytes`
+fn impl_frombytes_bounds<'a>(
+  name     : &syn::Ident,
+  mut generics : syn::Generics,
+  fields   : impl Iterator<Item = &'a syn::Field>,
+) -> TokenStream2
+{
+  {
+    let where_clause = generics.make_where_clause();
+    for field in fields {
+      let ty = &field.ty;
+      where_clause.predicates.push(
+        syn::parse_quote!(#ty: fromzeros::FromBytes));
     }
-  }.into()
+  }
+
+  let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+  quote! {
+    unsafe impl #impl_generics fromzeros::FromBytes for #name #ty_generics
+    #where_clause
+    {}
+  }
+}
+
+// number of bits in the enum's backing integer, if it has a primitive repr
+fn backing_repr_bits(attrs: &[syn::Attribute]) -> Option<u32> {
+  repr::Repr::from_attrs(attrs).primitive.map(repr::Primitive::bits)
+}
+
+// `true` if an enum with `variants` fieldless variants enumerates every value
+// of a `bits`-wide backing integer. `1 << bits` would overflow for the wider
+// reprs, which also means no enum could plausibly enumerate them all.
+fn enum_covers_repr(variants: usize, bits: u32) -> bool {
+  bits < 128 && variants as u128 == 1u128 << bits
+}
+
+// implement `FromZeros` for a `#[repr(transparent)]` struct
+//
+// We cannot tell which fields are zero-sized from the token stream alone, so
+// the non-zero-sized field is not singled out: as for any other struct, every
+// field is zeroed and every field type is bounded on `FromZeros`, zero-sized
+// ones included. A transparent type with no fields at all is always
+// ill-formed, so we reject it here.
+fn impl_fromzeros_transparent(
+  name      : &syn::Ident,
+  generics  : syn::Generics,
+  fields    : &syn::Fields,
+  validator : Option<&syn::Expr>,
+) -> Result<TokenStream2, Vec<syn::Error>>
+{
+  if fields.iter().count() == 0 {
+    return Err(vec![syn::Error::new_spanned(name, format!(
+      "{} is `repr(transparent)` but has no field to be transparent over.",
+      name))]);
+  }
+
+  Ok(impl_fromzeros_with(name, generics, None, fields, validator))
 }
 
 // implement `FromZeros` for a given type
-fn impl_fromzeros(
+fn impl_fromzeros_with(
   name      : &syn::Ident,
   generics  : syn::Generics,
   variant   : Option<&syn::Ident>,
-  fields    : &syn::Fields
+  fields    : &syn::Fields,
+  validator : Option<&syn::Expr>,
 ) -> TokenStream2
 {
   let zeroed = zeroed_fields(fields);
-  let generics = add_trait_bounds(generics);
+  let generics = add_trait_bounds(generics, fields);
   let (impl_generics, ty_generics, where_clause) =  generics.split_for_impl();
 
+  // When a validator is supplied, override `try_zeroed` to run it against the
+  // zeroed value. The value and the validator binding carry mangled names so
+  // that the user's validator expression cannot capture anything from the
+  // generated function body.
+  let try_zeroed = validator.map(|validator| quote! {
+    #[inline(always)]
+    fn try_zeroed() -> Option<Self>
+    where Self: Sized
+    {
+      let __fromzeros_validator: fn(&Self) -> bool = #validator;
+      let __fromzeros_candidate = <Self as fromzeros::FromZeros>::zeroed();
+      if __fromzeros_validator(&__fromzeros_candidate) {
+        Some(__fromzeros_candidate)
+      } else {
+        None
+      }
+    }
+  });
+
   return quote! {
     unsafe impl #impl_generics fromzeros::FromZeros for #name #ty_generics
     #where_clause
@@ -77,17 +308,30 @@ fn impl_fromzeros(
       {
         #name #(:: #variant)* #zeroed
       }
+
+      #try_zeroed
     }
   };
 
   // helper functions:
 
-  // adds `FromZeros` bounds to each generic parameter
-  fn add_trait_bounds(mut generics: syn::Generics) -> syn::Generics {
-    for param in &mut generics.params {
-        if let syn::GenericParam::Type(ref mut type_param) = *param {
-            type_param.bounds.push(syn::parse_quote!(fromzeros::FromZeros));
-        }
+  // bound each field *type* on `FromZeros`, rather than each type *parameter*
+  //
+  // Bounding the parameters would force spurious constraints on phantom type
+  // params: `struct S<T> { marker: PhantomData<T>, count: usize }` is zeroable
+  // for any `T`, but a blanket `where T: FromZeros` would demand otherwise.
+  // Predicating on the field types instead lets `PhantomData<T>: FromZeros`
+  // (which holds for all `T`) discharge the obligation without touching `T`.
+  fn add_trait_bounds(mut generics: syn::Generics, fields: &syn::Fields)
+    -> syn::Generics
+  {
+    {
+      let where_clause = generics.make_where_clause();
+      for field in fields.iter() {
+        let ty = &field.ty;
+        where_clause.predicates.push(
+          syn::parse_quote!(#ty: fromzeros::FromZeros));
+      }
     }
     generics
   }
@@ -125,33 +369,70 @@ fn impl_fromzeros(
 }
 
 // given an enum, produce the variant with a zero discriminant, if any
+//
+// Discriminants are resolved exactly the way rustc assigns them: the first
+// variant defaults to `0`, every variant without an explicit discriminant is
+// the previous variant's discriminant plus one, and an explicit discriminant
+// may be a constant expression (e.g. `D = 1 + 1`). We therefore walk the
+// variants maintaining a running counter and return the first variant whose
+// resolved discriminant is zero.
+//
+// If an explicit discriminant is an expression we cannot fold to a constant,
+// we bail out of the search entirely rather than risk accepting a variant that
+// is not actually zero.
 fn zero_variant(ast: &syn::DataEnum) -> Option<&syn::Variant> {
-  let mut variants = ast.variants.iter();
+  let mut counter: i128 = 0;
 
-  let first = variants.next()?;
+  for variant in ast.variants.iter() {
+    if let Some((_, ref expr)) = variant.discriminant {
+      // an unfoldable expression poisons every subsequent auto-increment, so
+      // we cannot reason about any later variant either
+      counter = fold_discriminant(expr)?;
+    }
 
-  // the discriminant of the first variant is implicitly zero unless specified
-  let first_discriminant = explicit_discriminant(first).unwrap_or(0);
+    if counter == 0 {
+      return Some(variant);
+    }
 
-  if first_discriminant == 0 {
-    return Some(first);
-  } else {
-    return variants.find(|variant| explicit_discriminant(variant).contains(&0));
+    counter += 1;
   }
 
+  return None;
+
   // helpers:
 
-  // given a variant, produce the value of its explicit discriminant, if any
-  fn explicit_discriminant(variant: &syn::Variant) -> Option<u64> {
-    if_chain! {
-      if let Some((_, ref disr)) = variant.discriminant;
-      if let syn::Expr::Lit(disr) = disr;
-      if let syn::Lit::Int(ref disr) = disr.lit;
-      then {
-        Some(disr.value())
-      } else {
-        None
-      }
+  // fold a discriminant expression to a constant, if possible
+  //
+  // Supports the subset of constant expressions that appear in practice as
+  // enum discriminants: integer literals, unary negation, and binary `+`, `-`
+  // and `*`. Anything else (paths to `const`s, function calls, ...) cannot be
+  // evaluated here and yields `None`.
+  fn fold_discriminant(expr: &syn::Expr) -> Option<i128> {
+    match expr {
+      syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(int), .. })
+        => Some(int.value() as i128),
+
+      syn::Expr::Unary(syn::ExprUnary { op: syn::UnOp::Neg(_), expr, .. })
+        => fold_discriminant(expr).map(|value| -value),
+
+      syn::Expr::Binary(syn::ExprBinary { left, op, right, .. }) => {
+        let left = fold_discriminant(left)?;
+        let right = fold_discriminant(right)?;
+        match op {
+          syn::BinOp::Add(_) => Some(left + right),
+          syn::BinOp::Sub(_) => Some(left - right),
+          syn::BinOp::Mul(_) => Some(left * right),
+          _ => None,
+        }
+      },
+
+      syn::Expr::Paren(syn::ExprParen { expr, .. })
+        => fold_discriminant(expr),
+
+      syn::Expr::Group(syn::ExprGroup { expr, .. })
+        => fold_discriminant(expr),
+
+      _ => None,
     }
   }
 
@@ -167,49 +448,108 @@ fn is_clike(ast: &syn::DataEnum) -> bool {
       })
 }
 
-fn has_primitive_repr(attrs: &[syn::Attribute]) -> bool {
-  return if_chain!{
-    if let Some(repr_attr) = attrs.iter().find_map(repr);
-    then {
-      repr_attr.nested.iter().any(is_primitive)
-    } else {
-      false
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // parse an `enum { ... }` body into a `DataEnum` for discriminant testing
+  fn parse_enum(source: &str) -> syn::DataEnum {
+    let item: syn::ItemEnum = syn::parse_str(source).unwrap();
+    syn::DataEnum {
+      enum_token: item.enum_token,
+      brace_token: item.brace_token,
+      variants: item.variants,
     }
-  };
+  }
 
-  // helper functions:
+  // the identifier of the zero-discriminant variant, if any
+  fn zero_variant_ident(source: &str) -> Option<String> {
+    zero_variant(&parse_enum(source)).map(|v| v.ident.to_string())
+  }
 
-  // produces the repr attr, if any
-  fn repr(ast: &syn::Attribute) -> Option<syn::MetaList> {
-    if_chain! {
-      if let Some(attr) = ast.interpret_meta();
-      if let syn::Meta::List(attr) = attr;
-      if attr.ident == "repr";
-      then {
-        return Some(attr)
-      } else {
-        return None
-      }
-    };
-  }
-
-  // produces true if the representation is primitive
-  fn is_primitive(meta: &syn::NestedMeta) -> bool {
-    const VALID: [&str; 12] =
-      [
-        "i8", "i16", "i32", "i64", "i128", "isize",
-        "u8", "u16", "u32", "u64", "u128", "usize",
-      ];
-
-    if_chain! {
-      if let syn::NestedMeta::Meta(meta) = meta;
-      if let syn::Meta::Word(repr) = meta;
-      then {
-        return VALID.iter().any(|t| repr == t);
-      } else {
-        return false;
-      }
-    }
+  #[test]
+  fn first_variant_is_implicitly_zero() {
+    assert_eq!(zero_variant_ident("enum E { A, B }").as_deref(), Some("A"));
+  }
+
+  #[test]
+  fn explicit_zero_discriminant() {
+    assert_eq!(zero_variant_ident("enum E { A = 1, B = 0 }").as_deref(), Some("B"));
+  }
+
+  #[test]
+  fn negative_discriminant_increments_to_zero() {
+    // `A = -1` puts `B` on the auto-incremented discriminant `0`
+    assert_eq!(zero_variant_ident("enum E { A = -1, B }").as_deref(), Some("B"));
+  }
+
+  #[test]
+  fn folded_expression_discriminants() {
+    // `D = 1 + 1` evaluates to 2, and `E`, `F` auto-increment to 3, 4
+    assert_eq!(zero_variant_ident("enum E { D = 1 + 1, E, F }"), None);
+    // `1 + (-1)` folds to 0
+    assert_eq!(zero_variant_ident("enum E { A = 1, C = 1 + (-1) }").as_deref(), Some("C"));
+  }
+
+  #[test]
+  fn no_zero_discriminant() {
+    assert_eq!(zero_variant_ident("enum E { A = 1, B }"), None);
   }
 
+  #[test]
+  fn unfoldable_discriminant_bails() {
+    // a discriminant we cannot fold poisons the search rather than defaulting
+    // to zero
+    assert_eq!(zero_variant_ident("enum E { A = SOME_CONST, B }"), None);
+  }
+
+  // the backing-integer width parsed from a type's repr attributes
+  fn repr_bits(source: &str) -> Option<u32> {
+    let ast: syn::DeriveInput = syn::parse_str(source).unwrap();
+    backing_repr_bits(&ast.attrs)
+  }
+
+  #[test]
+  fn backing_repr_bits_reads_primitive_repr() {
+    assert_eq!(repr_bits("#[repr(u8)] enum E { A }"), Some(8));
+    assert_eq!(repr_bits("#[repr(C, u16)] enum E { A }"), Some(16));
+    assert_eq!(repr_bits("#[repr(C)] enum E { A }"), None);
+    assert_eq!(repr_bits("enum E { A }"), None);
+  }
+
+  // the parsed repr of a type
+  fn parse_repr(source: &str) -> repr::Repr {
+    let ast: syn::DeriveInput = syn::parse_str(source).unwrap();
+    repr::Repr::from_attrs(&ast.attrs)
+  }
+
+  #[test]
+  fn repr_parses_structured_hints() {
+    let repr = parse_repr("#[repr(C, align(4))] struct S(u8);");
+    assert!(repr.is_c());
+    assert_eq!(repr.align, Some(4));
+
+    let repr = parse_repr("#[repr(transparent)] struct S(u8);");
+    assert!(repr.is_transparent());
+
+    let repr = parse_repr("#[repr(packed(2))] struct S(u8);");
+    assert_eq!(repr.packed, Some(Some(2)));
+  }
+
+  #[test]
+  fn repr_detects_packed_mismatches() {
+    assert!(parse_repr("#[repr(packed, align(2))] struct S(u8);").mismatch().is_some());
+    assert!(parse_repr("#[repr(transparent, packed)] struct S(u8);").mismatch().is_some());
+    assert!(parse_repr("#[repr(C, packed)] struct S(u8);").mismatch().is_none());
+  }
+
+  #[test]
+  fn frombytes_enum_requires_full_coverage() {
+    // a `u8`-repr enum is `FromBytes` only once all 256 values are named
+    assert!(!enum_covers_repr(2, 8));
+    assert!(enum_covers_repr(256, 8));
+    // wider reprs can never be exhaustively enumerated
+    assert!(!enum_covers_repr(2, 128));
+    assert!(!enum_covers_repr(usize::max_value(), 64));
+  }
 }
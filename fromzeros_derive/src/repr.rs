@@ -0,0 +1,151 @@
+//! Parsing of `#[repr(...)]` attributes into a structured form.
+//!
+//! `syn` hands us `#[repr(...)]` as an opaque nested-meta list; this module
+//! folds every recognized hint into a single [`Repr`] so the rest of the
+//! derive can ask precise questions ("is this a `repr(C)` enum?", "is this a
+//! transparent newtype?", "are these repr hints mutually contradictory?")
+//! instead of scanning the raw tokens.
+
+use if_chain::if_chain;
+
+// A primitive integer representation, e.g. the `u8` in `#[repr(u8)]`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Primitive {
+  I8, I16, I32, I64, I128, Isize,
+  U8, U16, U32, U64, U128, Usize,
+}
+
+impl Primitive {
+  fn from_ident(ident: &syn::Ident) -> Option<Primitive> {
+    Some(match ident.to_string().as_str() {
+      "i8"    => Primitive::I8,
+      "i16"   => Primitive::I16,
+      "i32"   => Primitive::I32,
+      "i64"   => Primitive::I64,
+      "i128"  => Primitive::I128,
+      "isize" => Primitive::Isize,
+      "u8"    => Primitive::U8,
+      "u16"   => Primitive::U16,
+      "u32"   => Primitive::U32,
+      "u64"   => Primitive::U64,
+      "u128"  => Primitive::U128,
+      "usize" => Primitive::Usize,
+      _ => return None,
+    })
+  }
+
+  // The width, in bits, of this integer. `isize`/`usize` are reported as 64;
+  // they can never be exhaustively enumerated, so the precise width is moot.
+  pub(crate) fn bits(self) -> u32 {
+    match self {
+      Primitive::I8  | Primitive::U8  => 8,
+      Primitive::I16 | Primitive::U16 => 16,
+      Primitive::I32 | Primitive::U32 => 32,
+      Primitive::I64 | Primitive::U64 => 64,
+      Primitive::I128 | Primitive::U128 => 128,
+      Primitive::Isize | Primitive::Usize => 64,
+    }
+  }
+}
+
+// The structured contents of a type's `#[repr(...)]` attributes.
+//
+// Hints combine: `#[repr(C, u8)]` sets both `c` and `primitive`, and a type
+// may carry several `#[repr(...)]` attributes, all of which are folded in.
+#[derive(Default)]
+pub(crate) struct Repr {
+  pub(crate) c: bool,
+  pub(crate) transparent: bool,
+  pub(crate) primitive: Option<Primitive>,
+  pub(crate) packed: Option<Option<u64>>,
+  pub(crate) align: Option<u64>,
+}
+
+impl Repr {
+  // Fold every `#[repr(...)]` attribute on `attrs` into a single `Repr`.
+  pub(crate) fn from_attrs(attrs: &[syn::Attribute]) -> Repr {
+    let mut repr = Repr::default();
+
+    for meta in attrs.iter().filter_map(repr_list) {
+      for nested in meta.nested {
+        match nested {
+          syn::NestedMeta::Meta(syn::Meta::Word(ref word)) if word == "C"
+            => repr.c = true,
+          syn::NestedMeta::Meta(syn::Meta::Word(ref word)) if word == "transparent"
+            => repr.transparent = true,
+          syn::NestedMeta::Meta(syn::Meta::Word(ref word)) if word == "packed"
+            => repr.packed = Some(None),
+          syn::NestedMeta::Meta(syn::Meta::Word(ref word)) => {
+            if let Some(primitive) = Primitive::from_ident(word) {
+              repr.primitive = Some(primitive);
+            }
+          },
+          syn::NestedMeta::Meta(syn::Meta::List(ref list)) if list.ident == "packed"
+            => repr.packed = Some(int_arg(&list.nested)),
+          syn::NestedMeta::Meta(syn::Meta::List(ref list)) if list.ident == "align"
+            => repr.align = int_arg(&list.nested),
+          _ => {},
+        }
+      }
+    }
+
+    repr
+  }
+
+  // `true` if this is a `#[repr(C)]` type.
+  pub(crate) fn is_c(&self) -> bool {
+    self.c
+  }
+
+  // `true` if this is a `#[repr(transparent)]` type.
+  pub(crate) fn is_transparent(&self) -> bool {
+    self.transparent
+  }
+
+  // `true` if this type carries a primitive integer repr.
+  pub(crate) fn is_primitive(&self) -> bool {
+    self.primitive.is_some()
+  }
+
+  // Describe contradictory repr hints involving `repr(packed)`: it cannot be
+  // combined with `repr(align(N))` or with `repr(transparent)`. Other
+  // combinations that rustc rejects (e.g. `repr(transparent, C)`) are not
+  // checked here.
+  pub(crate) fn mismatch(&self) -> Option<&'static str> {
+    if self.packed.is_some() && self.align.is_some() {
+      Some("`repr(packed)` and `repr(align(N))` are mutually exclusive")
+    } else if self.packed.is_some() && self.transparent {
+      Some("`repr(packed)` cannot be combined with `repr(transparent)`")
+    } else {
+      None
+    }
+  }
+}
+
+// produce the nested meta of a `#[repr(...)]` attribute, if this is one
+fn repr_list(attr: &syn::Attribute) -> Option<syn::MetaList> {
+  if_chain! {
+    if let Some(syn::Meta::List(list)) = attr.interpret_meta();
+    if list.ident == "repr";
+    then {
+      Some(list)
+    } else {
+      None
+    }
+  }
+}
+
+// produce the integer argument of a single-element meta list such as the `8`
+// in `align(8)` or `packed(2)`
+fn int_arg(nested: &syn::punctuated::Punctuated<syn::NestedMeta, syn::token::Comma>)
+  -> Option<u64>
+{
+  if_chain! {
+    if let Some(syn::NestedMeta::Literal(syn::Lit::Int(int))) = nested.first().map(|p| p.into_value());
+    then {
+      Some(int.value())
+    } else {
+      None
+    }
+  }
+}